@@ -1,21 +1,53 @@
-use super::primitives::{Address, Block, Money, Transaction};
+use super::primitives::{Address, Block, Money, Transaction, TransactionOutput};
 
 use db_key::Key;
+use futures::stream::{self, Stream, StreamExt};
 use leveldb::batch::Batch;
 use leveldb::database::batch::Writebatch;
 use leveldb::database::Database;
+use leveldb::iterator::{Iterable, LevelDBIterator};
 use leveldb::kv::KV;
 use leveldb::options::{Options, ReadOptions, WriteOptions};
+use rocksdb::{WriteBatch as RocksWriteBatch, DB};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
+use std::sync::RwLock;
 
 pub trait Blockchain {
     fn get_balance(&self, addr: Address) -> Money;
-    fn extend(&mut self, blocks: &Vec<Block>);
+    fn extend(&mut self, blocks: &Vec<Block>) -> Result<(), BlockchainError>;
     fn get_height(&self) -> usize;
+    fn validate_contract_payment(&self, tx: &Transaction, now: u64) -> Result<bool, BlockchainError>;
 }
 
+// Relative-locktime (BIP68/112-style) sequence flags.
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+const SEQUENCE_LOCKTIME_GRANULARITY_SECONDS: u64 = 512;
+
+#[derive(Debug)]
+pub enum BlockchainError {
+    KvStore(KvStoreError),
+    InsufficientFunds,
+    BalanceOverflow,
+}
+
+impl From<KvStoreError> for BlockchainError {
+    fn from(e: KvStoreError) -> Self {
+        BlockchainError::KvStore(e)
+    }
+}
+
+// Bookkeeping kept alongside a mempool entry, e.g. for eviction of stale
+// transactions.
 #[derive(Clone, Debug)]
+pub struct TransactionStats {
+    pub first_seen: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct StringKey(String);
 
 impl StringKey {
@@ -59,6 +91,28 @@ pub trait KvStore {
     fn del(&self, k: StringKey) -> Result<(), KvStoreError>;
     fn set(&self, k: StringKey, v: Vec<u8>) -> Result<(), KvStoreError>;
     fn batch(&self, ops: Vec<WriteOp>) -> Result<(), KvStoreError>;
+    // Ordered iteration over every key starting with `prefix`, e.g. all
+    // `addr_*` balances. Returned as a Stream rather than a materialized
+    // Vec so callers can paginate large key ranges (state-sync, balance
+    // export) without loading the whole keyspace into memory.
+    fn scan_prefix(
+        &self,
+        prefix: StringKey,
+    ) -> impl Stream<Item = Result<(StringKey, Vec<u8>), KvStoreError>>;
+}
+
+// Convenience wrapper for scan_prefix when the caller knows the range is
+// small enough to want it fully materialized.
+pub async fn scan_prefix_collect<K: KvStore>(
+    kv: &K,
+    prefix: StringKey,
+) -> Result<Vec<(StringKey, Vec<u8>)>, KvStoreError> {
+    let mut stream = Box::pin(kv.scan_prefix(prefix));
+    let mut out = Vec::new();
+    while let Some(item) = stream.next().await {
+        out.push(item?);
+    }
+    Ok(out)
 }
 
 pub struct LevelDbKvStore(Database<StringKey>);
@@ -107,6 +161,236 @@ impl KvStore for LevelDbKvStore {
             Err(_) => Err(KvStoreError::Failure),
         }
     }
+    fn scan_prefix(
+        &self,
+        prefix: StringKey,
+    ) -> impl Stream<Item = Result<(StringKey, Vec<u8>), KvStoreError>> {
+        let read_opts = ReadOptions::new();
+        let mut iter = self.0.iter(read_opts);
+        // Seek straight to the prefix instead of walking the keyspace
+        // from the start, and stop as soon as we're past it, so this
+        // scales with the size of the matching range rather than the
+        // whole store.
+        iter.seek(&prefix);
+        let matches = iter
+            .take_while(move |(k, _)| k.0.starts_with(&prefix.0))
+            .map(|(k, v)| Ok((k, v)));
+        stream::iter(matches)
+    }
+}
+
+pub struct RocksDbKvStore(DB);
+impl RocksDbKvStore {
+    pub fn new(path: &Path) -> RocksDbKvStore {
+        fs::create_dir_all(&path).unwrap();
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        RocksDbKvStore(DB::open(&options, path).unwrap())
+    }
+}
+
+impl KvStore for RocksDbKvStore {
+    fn get(&self, k: StringKey) -> Result<Option<Vec<u8>>, KvStoreError> {
+        match self.0.get(k.0.as_bytes()) {
+            Ok(v) => Ok(v.map(|v| v.to_vec())),
+            Err(_) => Err(KvStoreError::Failure),
+        }
+    }
+    fn set(&self, k: StringKey, v: Vec<u8>) -> Result<(), KvStoreError> {
+        match self.0.put(k.0.as_bytes(), &v) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(KvStoreError::Failure),
+        }
+    }
+    fn del(&self, k: StringKey) -> Result<(), KvStoreError> {
+        match self.0.delete(k.0.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(KvStoreError::Failure),
+        }
+    }
+    fn batch(&self, ops: Vec<WriteOp>) -> Result<(), KvStoreError> {
+        let mut batch = RocksWriteBatch::default();
+        for op in ops.into_iter() {
+            match op {
+                WriteOp::Remove(k) => batch.delete(k.0.as_bytes()),
+                WriteOp::Put(k, v) => batch.put(k.0.as_bytes(), &v),
+            }
+        }
+        match self.0.write(batch) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(KvStoreError::Failure),
+        }
+    }
+    fn scan_prefix(
+        &self,
+        prefix: StringKey,
+    ) -> impl Stream<Item = Result<(StringKey, Vec<u8>), KvStoreError>> {
+        // `prefix_iterator` only seeks to `prefix` and iterates forward;
+        // without a matching prefix_extractor configured on the column
+        // family it does not stop at the prefix boundary on its own, so
+        // the boundary is checked explicitly here rather than trusted to
+        // the default Options.
+        let iter = self
+            .0
+            .prefix_iterator(prefix.0.as_bytes())
+            .map_while(move |res| match res {
+                Ok((k, v)) => {
+                    let key = std::str::from_utf8(&k).unwrap().to_string();
+                    key.starts_with(&prefix.0)
+                        .then(|| (StringKey::new(&key), v.to_vec()))
+                        .map(Ok)
+                }
+                Err(_) => Some(Err(KvStoreError::Failure)),
+            });
+        stream::iter(iter)
+    }
+}
+
+// In-memory KvStore backed by a BTreeMap, used for deterministic unit
+// testing of KvStoreChain without touching disk.
+pub struct MemoryKvStore(RwLock<BTreeMap<String, Vec<u8>>>);
+
+impl MemoryKvStore {
+    pub fn new() -> MemoryKvStore {
+        MemoryKvStore(RwLock::new(BTreeMap::new()))
+    }
+}
+
+impl Default for MemoryKvStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KvStore for MemoryKvStore {
+    fn get(&self, k: StringKey) -> Result<Option<Vec<u8>>, KvStoreError> {
+        Ok(self.0.read().unwrap().get(&k.0).cloned())
+    }
+    fn set(&self, k: StringKey, v: Vec<u8>) -> Result<(), KvStoreError> {
+        self.0.write().unwrap().insert(k.0, v);
+        Ok(())
+    }
+    fn del(&self, k: StringKey) -> Result<(), KvStoreError> {
+        self.0.write().unwrap().remove(&k.0);
+        Ok(())
+    }
+    fn batch(&self, ops: Vec<WriteOp>) -> Result<(), KvStoreError> {
+        let mut db = self.0.write().unwrap();
+        for op in ops.into_iter() {
+            match op {
+                WriteOp::Remove(k) => {
+                    db.remove(&k.0);
+                }
+                WriteOp::Put(k, v) => {
+                    db.insert(k.0, v);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn scan_prefix(
+        &self,
+        prefix: StringKey,
+    ) -> impl Stream<Item = Result<(StringKey, Vec<u8>), KvStoreError>> {
+        // The read lock can't be held across the stream's lifetime, so the
+        // matching keys (cheap) are taken up front; unlike the eager
+        // version this used to be, values (the part that can actually be
+        // large) are fetched one at a time as the stream is polled,
+        // instead of all being cloned into memory immediately.
+        let keys: Vec<String> = {
+            let db = self.0.read().unwrap();
+            db.range(prefix.0.clone()..)
+                .take_while(|(k, _)| k.starts_with(&prefix.0))
+                .map(|(k, _)| k.clone())
+                .collect()
+        };
+        stream::iter(keys).filter_map(move |k| async move {
+            let key = StringKey::new(&k);
+            // The key can be deleted between the snapshot above and this
+            // fetch; rather than fabricate an empty value for it, just
+            // drop it from the results, same as if it had never matched.
+            match self.get(key.clone()) {
+                Ok(Some(value)) => Some(Ok((key, value))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+}
+
+const HEIGHT_KEY: &str = "height";
+
+fn undo_key(height: usize) -> StringKey {
+    StringKey::new(&format!("undo_{}", height))
+}
+
+// Key an address's last confirmation (height, timestamp) is stored under,
+// used to evaluate relative locktimes against the state it was last
+// touched in.
+fn confirmed_key(addr: &Address) -> StringKey {
+    StringKey::new(&format!("confirmed_{:?}", addr))
+}
+
+fn encode_confirmation(height: usize, timestamp: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16);
+    buf.extend_from_slice(&(height as u64).to_le_bytes());
+    buf.extend_from_slice(&timestamp.to_le_bytes());
+    buf
+}
+
+fn decode_confirmation(bytes: &[u8]) -> (usize, u64) {
+    let mut hbuf = [0u8; 8];
+    hbuf.copy_from_slice(&bytes[0..8]);
+    let mut tbuf = [0u8; 8];
+    tbuf.copy_from_slice(&bytes[8..16]);
+    (u64::from_le_bytes(hbuf) as usize, u64::from_le_bytes(tbuf))
+}
+
+// Minimal length-prefixed encoding for a list of WriteOps, so a batch of
+// forward writes can be stored as the value of a single undo-journal key.
+fn encode_write_ops(ops: &[WriteOp]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for op in ops {
+        match op {
+            WriteOp::Put(k, v) => {
+                buf.push(1u8);
+                buf.extend_from_slice(&(k.0.len() as u32).to_le_bytes());
+                buf.extend_from_slice(k.0.as_bytes());
+                buf.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                buf.extend_from_slice(v);
+            }
+            WriteOp::Remove(k) => {
+                buf.push(0u8);
+                buf.extend_from_slice(&(k.0.len() as u32).to_le_bytes());
+                buf.extend_from_slice(k.0.as_bytes());
+            }
+        }
+    }
+    buf
+}
+
+fn decode_write_ops(bytes: &[u8]) -> Vec<WriteOp> {
+    let mut ops = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let tag = bytes[i];
+        i += 1;
+        let klen = u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+        i += 4;
+        let key = StringKey::new(std::str::from_utf8(&bytes[i..i + klen]).unwrap());
+        i += klen;
+        match tag {
+            1 => {
+                let vlen = u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+                i += 4;
+                let value = bytes[i..i + vlen].to_vec();
+                i += vlen;
+                ops.push(WriteOp::Put(key, value));
+            }
+            _ => ops.push(WriteOp::Remove(key)),
+        }
+    }
+    ops
 }
 
 pub struct KvStoreChain<K: KvStore> {
@@ -117,7 +401,97 @@ impl<K: KvStore> KvStoreChain<K> {
     pub fn new(kv_store: K) -> KvStoreChain<K> {
         KvStoreChain::<K> { database: kv_store }
     }
-    fn apply_tx(tx: &Transaction) {}
+
+    // Reads a balance from the in-block overlay if this block has already
+    // touched `addr`, falling back to on-disk state otherwise. Keeps
+    // balance changes within a block visible to later transactions in
+    // that same block, instead of every tx reading stale pre-block state.
+    fn overlay_get_balance(&self, balances: &HashMap<StringKey, Money>, addr: &Address) -> Money {
+        match balances.get(&addr.get_key()) {
+            Some(&b) => b,
+            None => self.get_balance(addr.clone()),
+        }
+    }
+
+    fn apply_tx(
+        &self,
+        tx: &Transaction,
+        balances: &mut HashMap<StringKey, Money>,
+        confirmations: &mut HashMap<StringKey, Vec<u8>>,
+        height: usize,
+        timestamp: u64,
+    ) -> Result<(), BlockchainError> {
+        let mut src_balance = self.overlay_get_balance(balances, &tx.src);
+        for output in &tx.outputs {
+            let dst_balance = self
+                .overlay_get_balance(balances, &output.dst)
+                .checked_add(output.amount)
+                .ok_or(BlockchainError::BalanceOverflow)?;
+            src_balance = src_balance
+                .checked_sub(output.amount)
+                .ok_or(BlockchainError::InsufficientFunds)?;
+            balances.insert(output.dst.get_key(), dst_balance);
+            confirmations.insert(
+                confirmed_key(&output.dst),
+                encode_confirmation(height, timestamp),
+            );
+        }
+        // The fee is debited from the sender but not credited anywhere:
+        // there is no miner/coinbase to collect it into yet, so it is
+        // simply burned for now.
+        src_balance = src_balance
+            .checked_sub(tx.fee)
+            .ok_or(BlockchainError::InsufficientFunds)?;
+        balances.insert(tx.src.get_key(), src_balance);
+        confirmations.insert(confirmed_key(&tx.src), encode_confirmation(height, timestamp));
+        Ok(())
+    }
+
+    fn get_confirmation(&self, addr: &Address) -> Result<(usize, u64), KvStoreError> {
+        match self.database.get(confirmed_key(addr))? {
+            Some(b) => Ok(decode_confirmation(&b)),
+            None => Ok((0, 0)),
+        }
+    }
+
+    // Computes the inverse of a set of forward writes, so they can be
+    // undone later: a Put of the prior value for keys that existed, or a
+    // Remove for keys that were previously absent. Must be read against
+    // the database *before* the forward ops are applied.
+    fn invert_ops(&self, ops: &[WriteOp]) -> Result<Vec<WriteOp>, KvStoreError> {
+        let mut inverse = Vec::new();
+        for op in ops {
+            let key = match op {
+                WriteOp::Put(k, _) => k.clone(),
+                WriteOp::Remove(k) => k.clone(),
+            };
+            inverse.push(match self.database.get(key.clone())? {
+                Some(prev) => WriteOp::Put(key, prev),
+                None => WriteOp::Remove(key),
+            });
+        }
+        // Replay in reverse so overlapping keys within the same block
+        // land back on the value they held immediately before it.
+        inverse.reverse();
+        Ok(inverse)
+    }
+
+    // Rewinds the chain state to `height` by replaying undo-journal
+    // entries in reverse order, one block at a time. Makes `extend` safe
+    // to call speculatively during chain reorganizations.
+    pub fn rollback_to(&mut self, height: usize) -> Result<(), KvStoreError> {
+        let mut h = self.get_height();
+        while h > height {
+            h -= 1;
+            let key = undo_key(h);
+            if let Some(bytes) = self.database.get(key.clone())? {
+                self.database.batch(decode_write_ops(&bytes))?;
+            }
+            self.database.del(key)?;
+        }
+        self.database
+            .set(StringKey::new(HEIGHT_KEY), (height as u64).to_le_bytes().to_vec())
+    }
 }
 
 impl<K: KvStore> Blockchain for KvStoreChain<K> {
@@ -127,10 +501,358 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
             None => 0,
         }
     }
-    fn extend(&mut self, _blocks: &Vec<Block>) {
-        unimplemented!();
+    fn extend(&mut self, blocks: &Vec<Block>) -> Result<(), BlockchainError> {
+        for block in blocks {
+            let height = self.get_height();
+            // Balances/confirmations are accumulated in an overlay across
+            // the whole block, not written per-tx, so a later tx in the
+            // same block sees the effects of an earlier one instead of
+            // stale pre-block state. The overlay is pure scratch space: if
+            // any tx in the block fails to apply, the block is rejected
+            // before anything reaches `self.database`, so a failing tx
+            // (whether mid-tx on its own outputs, or a later tx in the
+            // same block) can never leave a partially-applied block on
+            // disk. This also means `extend` never panics on a bad block,
+            // which matters since it must stay safe to call speculatively
+            // during chain reorganizations.
+            let mut balances = HashMap::new();
+            let mut confirmations = HashMap::new();
+            for tx in &block.transactions {
+                self.apply_tx(tx, &mut balances, &mut confirmations, height, block.timestamp)?;
+            }
+            let forward_ops: Vec<WriteOp> = balances
+                .into_iter()
+                .map(|(k, v)| WriteOp::Put(k, v.to_le_bytes().to_vec()))
+                .chain(confirmations.into_iter().map(|(k, v)| WriteOp::Put(k, v)))
+                .collect();
+            let undo_ops = self.invert_ops(&forward_ops)?;
+
+            let mut batch_ops = forward_ops;
+            batch_ops.push(WriteOp::Put(undo_key(height), encode_write_ops(&undo_ops)));
+            batch_ops.push(WriteOp::Put(
+                StringKey::new(HEIGHT_KEY),
+                ((height + 1) as u64).to_le_bytes().to_vec(),
+            ));
+            self.database.batch(batch_ops)?;
+        }
+        Ok(())
     }
     fn get_height(&self) -> usize {
-        0
+        match self.database.get(StringKey::new(HEIGHT_KEY)).unwrap() {
+            Some(b) => {
+                let mut buf = [0u8; 8];
+                let n = b.len().min(8);
+                buf[..n].copy_from_slice(&b[..n]);
+                u64::from_le_bytes(buf) as usize
+            }
+            None => 0,
+        }
+    }
+    fn validate_contract_payment(&self, tx: &Transaction, now: u64) -> Result<bool, BlockchainError> {
+        // All-or-nothing: the sender must cover every output plus the fee,
+        // or the whole transaction is rejected. An overflowing total can
+        // never be affordable, so treat it as invalid rather than letting
+        // the addition wrap or panic.
+        let total = match tx
+            .outputs
+            .iter()
+            .try_fold(tx.fee, |acc, output| acc.checked_add(output.amount))
+        {
+            Some(total) => total,
+            None => return Ok(false),
+        };
+        if self.get_balance(tx.src.clone()) < total {
+            return Ok(false);
+        }
+
+        if tx.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 {
+            let (confirmed_height, confirmed_time) = self.get_confirmation(&tx.src)?;
+            let matured = if tx.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                let lock_seconds =
+                    (tx.sequence & SEQUENCE_LOCKTIME_MASK) as u64 * SEQUENCE_LOCKTIME_GRANULARITY_SECONDS;
+                now >= confirmed_time + lock_seconds
+            } else {
+                let lock_blocks = (tx.sequence & SEQUENCE_LOCKTIME_MASK) as usize;
+                self.get_height() >= confirmed_height + lock_blocks
+            };
+            if !matured {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shared assertions run against every KvStore backend so they stay
+    // behaviorally identical under both single-op and batched writes.
+    fn test_kv_store_behavior<K: KvStore>(kv: K) {
+        assert_eq!(kv.get(StringKey::new("a")).unwrap(), None);
+
+        kv.set(StringKey::new("a"), vec![1, 2, 3]).unwrap();
+        assert_eq!(kv.get(StringKey::new("a")).unwrap(), Some(vec![1, 2, 3]));
+
+        kv.del(StringKey::new("a")).unwrap();
+        assert_eq!(kv.get(StringKey::new("a")).unwrap(), None);
+
+        kv.batch(vec![
+            WriteOp::Put(StringKey::new("a"), vec![1]),
+            WriteOp::Put(StringKey::new("b"), vec![2]),
+        ])
+        .unwrap();
+        assert_eq!(kv.get(StringKey::new("a")).unwrap(), Some(vec![1]));
+        assert_eq!(kv.get(StringKey::new("b")).unwrap(), Some(vec![2]));
+
+        kv.batch(vec![
+            WriteOp::Remove(StringKey::new("a")),
+            WriteOp::Put(StringKey::new("b"), vec![3]),
+        ])
+        .unwrap();
+        assert_eq!(kv.get(StringKey::new("a")).unwrap(), None);
+        assert_eq!(kv.get(StringKey::new("b")).unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn test_undo_journal_roundtrip() {
+        let ops = vec![
+            WriteOp::Put(StringKey::new("a"), vec![1, 2]),
+            WriteOp::Remove(StringKey::new("b")),
+        ];
+        let decoded = decode_write_ops(&encode_write_ops(&ops));
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(&decoded[0], WriteOp::Put(k, v) if *k == StringKey::new("a") && *v == vec![1, 2]));
+        assert!(matches!(&decoded[1], WriteOp::Remove(k) if *k == StringKey::new("b")));
+    }
+
+    #[test]
+    fn test_rollback_to_restores_prior_state() {
+        let mut chain = KvStoreChain::new(MemoryKvStore::new());
+        assert_eq!(chain.get_height(), 0);
+
+        // Simulate what `extend` would have committed for a single block
+        // that wrote `addr_x`, so rollback_to can be exercised without a
+        // full Transaction/Block.
+        let height = chain.get_height();
+        chain.database.set(StringKey::new("addr_x"), vec![5]).unwrap();
+        let undo_ops = vec![WriteOp::Remove(StringKey::new("addr_x"))];
+        chain
+            .database
+            .batch(vec![WriteOp::Put(undo_key(height), encode_write_ops(&undo_ops))])
+            .unwrap();
+        chain
+            .database
+            .set(
+                StringKey::new(HEIGHT_KEY),
+                ((height + 1) as u64).to_le_bytes().to_vec(),
+            )
+            .unwrap();
+        assert_eq!(chain.get_height(), 1);
+
+        chain.rollback_to(0).unwrap();
+        assert_eq!(chain.get_height(), 0);
+        assert_eq!(chain.database.get(StringKey::new("addr_x")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_multi_recipient_contract_payment() {
+        let mut chain = KvStoreChain::new(MemoryKvStore::new());
+        let src = Address(vec![0]);
+        let dst1 = Address(vec![1]);
+        let dst2 = Address(vec![2]);
+        chain
+            .database
+            .set(src.get_key(), 10u8.to_le_bytes().to_vec())
+            .unwrap();
+
+        let tx = Transaction {
+            src: src.clone(),
+            outputs: vec![
+                TransactionOutput {
+                    dst: dst1.clone(),
+                    amount: 3,
+                },
+                TransactionOutput {
+                    dst: dst2.clone(),
+                    amount: 4,
+                },
+            ],
+            fee: 1,
+            sequence: 0,
+        };
+        assert!(chain.validate_contract_payment(&tx, 0).unwrap());
+
+        chain
+            .extend(&vec![Block {
+                transactions: vec![tx],
+                timestamp: 0,
+            }])
+            .unwrap();
+        // 10 - 3 - 4 - fee(1) = 2: the fee must actually be debited on
+        // apply, not just checked at admission time.
+        assert_eq!(chain.get_balance(src), 2);
+        assert_eq!(chain.get_balance(dst1), 3);
+        assert_eq!(chain.get_balance(dst2), 4);
+    }
+
+    #[test]
+    fn test_validate_contract_payment_rejects_overflowing_total() {
+        let chain = KvStoreChain::new(MemoryKvStore::new());
+        let src = Address(vec![0]);
+        let tx = Transaction {
+            src: src.clone(),
+            outputs: vec![
+                TransactionOutput {
+                    dst: Address(vec![1]),
+                    amount: 90,
+                },
+                TransactionOutput {
+                    dst: Address(vec![2]),
+                    amount: 90,
+                },
+                TransactionOutput {
+                    dst: Address(vec![3]),
+                    amount: 90,
+                },
+            ],
+            fee: 0,
+            sequence: 0,
+        };
+        assert!(!chain.validate_contract_payment(&tx, 0).unwrap());
+    }
+
+    #[test]
+    fn test_relative_locktime_blocks_immature_spend() {
+        let mut chain = KvStoreChain::new(MemoryKvStore::new());
+        let src = Address(vec![0]);
+        let dst = Address(vec![1]);
+        chain
+            .database
+            .set(src.get_key(), 10u8.to_le_bytes().to_vec())
+            .unwrap();
+
+        // src is confirmed at height 0; a tx requiring 2 confirmed blocks
+        // of relative locktime must not validate until height 2.
+        let locked_tx = Transaction {
+            src: src.clone(),
+            outputs: vec![TransactionOutput {
+                dst: dst.clone(),
+                amount: 1,
+            }],
+            fee: 0,
+            sequence: 2,
+        };
+        assert!(!chain.validate_contract_payment(&locked_tx, 0).unwrap());
+
+        chain
+            .extend(&vec![
+                Block {
+                    transactions: vec![],
+                    timestamp: 0,
+                },
+                Block {
+                    transactions: vec![],
+                    timestamp: 0,
+                },
+            ])
+            .unwrap();
+        assert!(chain.validate_contract_payment(&locked_tx, 0).unwrap());
+    }
+
+    #[test]
+    fn test_extend_rejects_whole_block_on_insufficient_funds() {
+        let mut chain = KvStoreChain::new(MemoryKvStore::new());
+        let src = Address(vec![0]);
+        let dst = Address(vec![1]);
+        chain
+            .database
+            .set(src.get_key(), 1u8.to_le_bytes().to_vec())
+            .unwrap();
+
+        let tx = Transaction {
+            src: src.clone(),
+            outputs: vec![TransactionOutput {
+                dst: dst.clone(),
+                amount: 5,
+            }],
+            fee: 0,
+            sequence: 0,
+        };
+        let err = chain
+            .extend(&vec![Block {
+                transactions: vec![tx],
+                timestamp: 0,
+            }])
+            .unwrap_err();
+        assert!(matches!(err, BlockchainError::InsufficientFunds));
+
+        // Nothing from the rejected block should have reached disk.
+        assert_eq!(chain.get_height(), 0);
+        assert_eq!(chain.get_balance(src), 1);
+        assert_eq!(chain.get_balance(dst), 0);
+    }
+
+    // Shared assertions run against every KvStore backend, mirroring
+    // test_kv_store_behavior, so scan_prefix's prefix-matching semantics
+    // are verified on the disk-backed backends and not just MemoryKvStore.
+    // Results are sorted before comparing, so the order a backend yields
+    // them in is not checked here.
+    async fn test_scan_prefix_behavior<K: KvStore>(kv: K) {
+        kv.set(StringKey::new("addr_1"), vec![1]).unwrap();
+        kv.set(StringKey::new("addr_2"), vec![2]).unwrap();
+        kv.set(StringKey::new("mempool_1"), vec![3]).unwrap();
+
+        let mut matches = scan_prefix_collect(&kv, StringKey::new("addr_"))
+            .await
+            .unwrap();
+        matches.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        assert_eq!(
+            matches,
+            vec![
+                (StringKey::new("addr_1"), vec![1]),
+                (StringKey::new("addr_2"), vec![2]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix_memory_kv_store() {
+        test_scan_prefix_behavior(MemoryKvStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix_leveldb_kv_store() {
+        let path = std::env::temp_dir().join("bazuka-test-scan-leveldb");
+        test_scan_prefix_behavior(LevelDbKvStore::new(&path)).await;
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix_rocksdb_kv_store() {
+        let path = std::env::temp_dir().join("bazuka-test-scan-rocksdb");
+        test_scan_prefix_behavior(RocksDbKvStore::new(&path)).await;
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_memory_kv_store() {
+        test_kv_store_behavior(MemoryKvStore::new());
+    }
+
+    #[test]
+    fn test_leveldb_kv_store() {
+        let path = std::env::temp_dir().join("bazuka-test-leveldb");
+        test_kv_store_behavior(LevelDbKvStore::new(&path));
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_rocksdb_kv_store() {
+        let path = std::env::temp_dir().join("bazuka-test-rocksdb");
+        test_kv_store_behavior(RocksDbKvStore::new(&path));
+        let _ = fs::remove_dir_all(&path);
     }
 }
@@ -0,0 +1,9 @@
+use crate::primitives::Transaction;
+
+#[derive(Clone, Debug)]
+pub struct TransactContractPaymentRequest {
+    pub tx: Transaction,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransactContractPaymentResponse {}
@@ -0,0 +1,4 @@
+use super::{NodeContext, NodeError};
+
+pub mod messages;
+pub mod transact_contract_payment;
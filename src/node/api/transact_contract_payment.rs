@@ -11,7 +11,7 @@ pub async fn transact_contract_payment<B: Blockchain>(
     let mut context = context.write().await;
     let now = context.network_timestamp();
     // Prevent spamming mempool
-    if context.blockchain.validate_contract_payment(&req.tx)? {
+    if context.blockchain.validate_contract_payment(&req.tx, now)? {
         context
             .contract_payment_mempool
             .insert(req.tx, TransactionStats { first_seen: now });
@@ -0,0 +1,31 @@
+use crate::blockchain::{Blockchain, BlockchainError, TransactionStats};
+use crate::primitives::Transaction;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod api;
+
+#[derive(Debug)]
+pub enum NodeError {
+    Blockchain(BlockchainError),
+}
+
+impl From<BlockchainError> for NodeError {
+    fn from(e: BlockchainError) -> Self {
+        NodeError::Blockchain(e)
+    }
+}
+
+pub struct NodeContext<B: Blockchain> {
+    pub blockchain: B,
+    pub contract_payment_mempool: HashMap<Transaction, TransactionStats>,
+}
+
+impl<B: Blockchain> NodeContext<B> {
+    pub fn network_timestamp(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
@@ -0,0 +1,27 @@
+pub type Money = u8;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Address(pub Vec<u8>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransactionOutput {
+    pub dst: Address,
+    pub amount: Money,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Transaction {
+    pub src: Address,
+    pub outputs: Vec<TransactionOutput>,
+    pub fee: Money,
+    // BIP68/112-style relative locktime. Bit 31 disables the lock
+    // entirely, bit 22 selects time-based (set) vs block-height-based
+    // (unset) units, and the low 16 bits hold the lock value.
+    pub sequence: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub transactions: Vec<Transaction>,
+    pub timestamp: u64,
+}